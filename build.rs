@@ -0,0 +1,11 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(feature = "grpc")]
+    {
+        tonic_build::configure().build_client(true).build_server(true).compile(
+            &["proto/popup.proto"],
+            &["proto"],
+        )?;
+        println!("cargo:rerun-if-changed=proto/popup.proto");
+    }
+    Ok(())
+}
@@ -16,5 +16,34 @@ pub async fn setup_application(app: &AppHandle) -> Result<()> {
         .await
         .map_err(|e| anyhow::anyhow!(e))?;
 
+    // 3) 如果配置了远端 MCP 的 gRPC endpoint，额外拨号连过去，复用同一份
+    //    IpcServerState，这样远端请求和本地 Unix socket/命名管道请求走同一套
+    //    广播/应答模型，前端完全无感知
+    #[cfg(feature = "grpc")]
+    {
+        use crate::ipc::grpc::run_ui_client;
+
+        if let Ok(endpoint) = std::env::var("HENGJING_IPC_GRPC_REMOTE") {
+            match crate::ipc::grpc::required_token_from_env() {
+                Ok(token) => {
+                    let server_state = {
+                        let state_guard = ipc_state.0.lock().await;
+                        state_guard.clone()
+                    };
+                    if let Some(server_state) = server_state {
+                        tokio::spawn(async move {
+                            if let Err(e) = run_ui_client(endpoint, token, server_state).await {
+                                crate::log_important!(error, "连接远端 MCP gRPC 服务失败: {}", e);
+                            }
+                        });
+                    }
+                }
+                Err(e) => {
+                    crate::log_important!(error, "未配置有效的 gRPC 鉴权 token，跳过远端连接: {}", e);
+                }
+            }
+        }
+    }
+
     Ok(())
 }
@@ -2,12 +2,55 @@
 //!
 //! 用于向已运行的 UI 进程发送请求
 
-use anyhow::{Result, Context};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use anyhow::{Context, Result};
+use tokio::io::{AsyncRead, AsyncWrite, BufReader};
 use tokio::time::{timeout, Duration};
 
+use super::framing::{read_frame, write_frame};
 use super::{get_socket_path, IpcRequest, IpcResponse};
 
+/// 通过已连接的流发送请求，在最终响应到达前把每条 `partial` 帧交给 `on_progress`，
+/// Unix/Windows 共用
+async fn exchange<S>(
+    stream: S,
+    request: &IpcRequest,
+    read_timeout: Duration,
+    mut on_progress: impl FnMut(String),
+) -> Result<String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+
+    let request_json = serde_json::to_vec(request)?;
+    write_frame(&mut writer, &request_json).await?;
+
+    let mut buf_reader = BufReader::new(reader);
+
+    // 服务端在最终答案之前可能会推送任意多条 partial 帧，逐帧读取直到遇到非 partial 的终帧；
+    // 我们总是以新版长度前缀格式发起请求，服务端会原样用同一种格式回复，故无需关心探测到的格式
+    loop {
+        let frame = timeout(read_timeout, read_frame(&mut buf_reader))
+            .await
+            .context("等待响应超时")?
+            .context("读取响应失败")?;
+        let (frame, _format) = frame.ok_or_else(|| anyhow::anyhow!("连接已关闭"))?;
+
+        let response: IpcResponse = serde_json::from_slice(&frame).context("解析响应失败")?;
+
+        if response.partial {
+            on_progress(response.response);
+            continue;
+        }
+
+        return if response.success {
+            Ok(response.response)
+        } else {
+            anyhow::bail!(response.error.unwrap_or_else(|| "未知错误".to_string()))
+        };
+    }
+}
+
 /// IPC 客户端
 pub struct IpcClient;
 
@@ -30,67 +73,97 @@ impl IpcClient {
         
         #[cfg(windows)]
         {
-            false // Windows 暂时返回 false，需要额外实现
+            use tokio::net::windows::named_pipe::ClientOptions;
+
+            // 命名管道没有独立于"连接"之外的"是否存在"探测手段：`open` 本身
+            // 就是完整的 `ConnectNamedPipe` 握手，成功即意味着吃掉了服务端一个
+            // 挂起的管道实例（触发一次 `handle_connection` 然后立刻因为这个
+            // 连接被丢弃而读到 EOF），探测完之后服务端得重新 `create` 一个实例
+            // 才能接下一个真正的客户端。这比理想情况多耗一轮实例周期，但命名
+            // 管道没有 Unix socket `path.exists()` 那种零代价的存在性检查，
+            // 要避免这个开销得自己调 Win32 `WaitNamedPipe`/区分
+            // `ERROR_FILE_NOT_FOUND`，目前偏向用 `tokio` 现有 API 保持实现简单
+            let pipe_name = socket_path.to_string_lossy().to_string();
+            ClientOptions::new().open(&pipe_name).is_ok()
         }
     }
-    
+
     /// 发送请求到已运行的 UI 进程
     ///
     /// 返回响应字符串，或者在连接失败时返回错误
     pub async fn send_request(request: &IpcRequest) -> Result<String> {
+        Self::send_request_with_progress(request, |_chunk| {}).await
+    }
+
+    /// 发送请求到已运行的 UI 进程，并在最终答案之前把每条流式进度消息交给 `on_progress`
+    ///
+    /// 调用方（例如 MCP 弹窗调用）可以把 `on_progress` 接到自己的进度通知上，
+    /// 从而把 UI 的打字状态/中间结果实时转发出去
+    pub async fn send_request_with_progress(
+        request: &IpcRequest,
+        on_progress: impl FnMut(String),
+    ) -> Result<String> {
         let socket_path = get_socket_path();
-        
+
         #[cfg(unix)]
         {
-            Self::send_request_unix(&socket_path, request).await
+            Self::send_request_unix(&socket_path, request, on_progress).await
         }
-        
+
         #[cfg(windows)]
         {
-            anyhow::bail!("Windows IPC 暂未实现")
+            Self::send_request_windows(&socket_path, request, on_progress).await
         }
     }
-    
+
     #[cfg(unix)]
-    async fn send_request_unix(socket_path: &std::path::Path, request: &IpcRequest) -> Result<String> {
+    async fn send_request_unix(
+        socket_path: &std::path::Path,
+        request: &IpcRequest,
+        on_progress: impl FnMut(String),
+    ) -> Result<String> {
         use tokio::net::UnixStream;
-        
+
         // 连接到 socket
         let stream = UnixStream::connect(socket_path)
             .await
             .context("无法连接到 UI 进程")?;
-        
-        let (reader, mut writer) = stream.into_split();
-        
-        // 发送请求
-        let request_json = serde_json::to_string(request)?;
-        writer.write_all(request_json.as_bytes()).await?;
-        writer.write_all(b"\n").await?;
-        writer.flush().await?;
-        
+
         // 等待响应（最长等待 10 分钟，因为用户可能需要较长时间输入）
-        let mut buf_reader = BufReader::new(reader);
-        let mut response_line = String::new();
-        
-        let read_result = timeout(
-            Duration::from_secs(600),
-            buf_reader.read_line(&mut response_line)
-        ).await
-            .context("等待响应超时")?
-            .context("读取响应失败")?;
-        
-        if read_result == 0 {
-            anyhow::bail!("连接已关闭");
-        }
-        
-        // 解析响应
-        let response: IpcResponse = serde_json::from_str(response_line.trim())
-            .context("解析响应失败")?;
-        
-        if response.success {
-            Ok(response.response)
-        } else {
-            anyhow::bail!(response.error.unwrap_or_else(|| "未知错误".to_string()))
-        }
+        exchange(stream, request, Duration::from_secs(600), on_progress).await
+    }
+
+    #[cfg(windows)]
+    async fn send_request_windows(
+        pipe_name: &std::path::Path,
+        request: &IpcRequest,
+        on_progress: impl FnMut(String),
+    ) -> Result<String> {
+        use tokio::net::windows::named_pipe::ClientOptions;
+
+        // Win32 ERROR_PIPE_BUSY，避免为了一个常量额外引入 windows-sys 依赖
+        const ERROR_PIPE_BUSY: i32 = 231;
+
+        let pipe_name = pipe_name.to_string_lossy().to_string();
+
+        // 管道忙时按 ~50ms 间隔重试，连接阶段最长 600s；连上之后每次等待响应帧
+        // 也各自最长 600s（和 Unix 分支一致）
+        let overall = Duration::from_secs(600);
+        let connect_fut = async {
+            loop {
+                match ClientOptions::new().open(&pipe_name) {
+                    Ok(client) => break Ok(client),
+                    Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY) => {
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                    }
+                    Err(e) => break Err(e).context("无法连接到 UI 进程"),
+                }
+            }
+        };
+        let stream = timeout(overall, connect_fut)
+            .await
+            .context("连接 UI 进程超时")??;
+
+        exchange(stream, request, overall, on_progress).await
     }
 }
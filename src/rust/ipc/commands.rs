@@ -1,10 +1,10 @@
 //! IPC Tauri 命令
 
 use std::sync::Arc;
-use tauri::{AppHandle, State, Emitter};
+use tauri::{AppHandle, Emitter, State, Window};
 use tokio::sync::Mutex;
 
-use super::server::IpcServerState;
+use super::server::{IpcServerState, OutboundEvent};
 use super::IpcRequest;
 use crate::log_important;
 
@@ -18,22 +18,126 @@ impl Default for IpcStateWrapper {
 }
 
 /// 发送 IPC 响应
+///
+/// `cancelled` 为 `true` 表示本窗口只是关闭了弹窗、并未真正作答（例如用户去另一个
+/// 窗口回答了），这种情况下请求仍然保持等待，留给其它窗口响应。
 #[tauri::command]
 pub async fn send_ipc_response(
     request_id: String,
     response: String,
+    cancelled: Option<bool>,
     ipc_state: State<'_, IpcStateWrapper>,
 ) -> Result<(), String> {
     let state_guard = ipc_state.0.lock().await;
     if let Some(state) = state_guard.as_ref() {
-        state.send_response(&request_id, response).await
+        state.send_response(&request_id, response, cancelled.unwrap_or(false)).await
             .map_err(|e| format!("发送 IPC 响应失败: {}", e))
     } else {
         Err("IPC 服务器未初始化".to_string())
     }
 }
 
+/// 发送一条流式消息，`is_final` 为 `false` 时是中间进度（打字状态/部分结果），
+/// 在最终响应之前可以调用任意多次；`is_final` 为 `true` 时这条 `chunk` 本身
+/// 就是终局答案，等价于调用 `send_ipc_response(request_id, chunk, false)`，
+/// 请求到此结束。
+#[tauri::command]
+pub async fn send_ipc_chunk(
+    request_id: String,
+    chunk: String,
+    is_final: bool,
+    ipc_state: State<'_, IpcStateWrapper>,
+) -> Result<(), String> {
+    let state_guard = ipc_state.0.lock().await;
+    let Some(state) = state_guard.as_ref() else {
+        return Err("IPC 服务器未初始化".to_string());
+    };
+    if is_final {
+        state.send_response(&request_id, chunk, false).await
+            .map_err(|e| format!("发送 IPC 响应失败: {}", e))
+    } else {
+        state.send_chunk(&request_id, chunk).await
+            .map_err(|e| format!("发送 IPC 流式进度失败: {}", e))
+    }
+}
+
+/// 注册当前窗口为一个 UI 连接，开始接收广播的弹窗请求
+///
+/// 每个窗口/设备各自拥有一个有界事件队列（见 `OutboundQueue`），消费太慢的窗口
+/// 只会丢失自己的积压弹窗并收到一条 `mcp-request-missed` 提示，不会影响其它窗口
+/// 或拖慢 MCP 调用方。
+#[tauri::command]
+pub async fn register_ipc_connection(
+    window: Window,
+    ipc_state: State<'_, IpcStateWrapper>,
+) -> Result<u64, String> {
+    let state = {
+        let state_guard = ipc_state.0.lock().await;
+        state_guard
+            .as_ref()
+            .cloned()
+            .ok_or_else(|| "IPC 服务器未初始化".to_string())?
+    };
+
+    let (connection_id, queue) = state.register_connection().await;
+
+    tokio::spawn(async move {
+        loop {
+            let event = match queue.recv().await {
+                Some(event) => event,
+                // 连接已经被 `unregister_ipc_connection` 注销（窗口正常关闭），
+                // 队列也排空了，没有事件要转发了，退出任务、放掉这份 `Arc`
+                None => break,
+            };
+            let (event_name, payload) = match event {
+                OutboundEvent::Popup(request) => (
+                    "mcp-request",
+                    serde_json::json!({
+                        "id": request.id,
+                        "message": request.message,
+                        "predefined_options": request.predefined_options,
+                        "is_markdown": request.is_markdown,
+                    }),
+                ),
+                OutboundEvent::AlreadyAnswered { request_id } => (
+                    "mcp-request-answered",
+                    serde_json::json!({ "id": request_id }),
+                ),
+                OutboundEvent::Missed => ("mcp-request-missed", serde_json::json!({})),
+            };
+
+            if let Err(e) = window.emit(event_name, payload) {
+                log_important!(error, "向窗口转发 IPC 事件失败，停止该连接: {}", e);
+                break;
+            }
+        }
+        state.unregister_connection(connection_id).await;
+    });
+
+    Ok(connection_id)
+}
+
+/// 注销一个 UI 连接（窗口关闭时由前端调用）
+#[tauri::command]
+pub async fn unregister_ipc_connection(
+    connection_id: u64,
+    ipc_state: State<'_, IpcStateWrapper>,
+) -> Result<(), String> {
+    let state_guard = ipc_state.0.lock().await;
+    if let Some(state) = state_guard.as_ref() {
+        state.unregister_connection(connection_id).await;
+    }
+    Ok(())
+}
+
 /// 启动 IPC 服务器并监听请求
+///
+/// 广播给已注册连接的 per-window 模型（[`register_ipc_connection`]）是本系列
+/// 新加的；没有同步迁移到调用 `register_ipc_connection` 的旧前端如果只靠之前
+/// 全局 `app.emit("ipc-mcp-request", ..)` 弹窗就会完全收不到请求，弹窗功能
+/// 直接哑掉。过渡期内继续原样保留这条全局兼容广播，新前端改走
+/// `register_ipc_connection` 之后按窗口收 `mcp-request`，两条路径的事件名不
+/// 重叠，不会导致同一个前端重复收到同一条弹窗。
 pub async fn start_ipc_server(
     app_handle: &AppHandle,
     ipc_state: Arc<Mutex<Option<Arc<IpcServerState>>>>,
@@ -51,33 +155,31 @@ pub async fn start_ipc_server(
     // 保存服务器状态
     {
         let mut state_guard = ipc_state.lock().await;
-        *state_guard = Some(server_state);
+        *state_guard = Some(Arc::clone(&server_state));
     }
 
     server.start().await.map_err(|e| format!("启动 IPC 服务器失败: {}", e))?;
 
-    // 在后台任务中监听请求并通过 Tauri 事件发送到前端
-    let app_handle_clone = app_handle.clone();
+    // 在后台任务中监听请求，一边广播给所有已注册的 UI 连接（窗口/设备），一边
+    // 保留全局 `ipc-mcp-request` 兼容事件给还没升级到 register_ipc_connection
+    // 的旧前端
+    let app_handle = app_handle.clone();
     tokio::spawn(async move {
         while let Some(request) = request_rx.recv().await {
-            log_important!(info, "转发 IPC 请求到前端: {}", request.id);
-            
-            // 将 IpcRequest 转换为前端可用的格式
-            let payload = serde_json::json!({
+            log_important!(info, "广播 IPC 请求到所有 UI 连接: {}", request.id);
+
+            // 兼容旧前端：全局广播一份，字段和新版 Popup 事件保持一致
+            let legacy_payload = serde_json::json!({
                 "id": request.id,
                 "message": request.message,
                 "predefined_options": request.predefined_options,
                 "is_markdown": request.is_markdown,
             });
-
-            // 通过 Tauri 事件发送到前端
-            // 约定：统一使用 `mcp-request`；同时保留 `ipc-mcp-request` 兼容旧前端
-            if let Err(e) = app_handle_clone.emit("mcp-request", payload.clone()) {
-                log_important!(error, "发送 MCP 请求事件失败: {}", e);
-            }
-            if let Err(e) = app_handle_clone.emit("ipc-mcp-request", payload) {
-                log_important!(error, "发送 IPC MCP 请求事件失败: {}", e);
+            if let Err(e) = app_handle.emit("ipc-mcp-request", legacy_payload) {
+                log_important!(error, "发送全局兼容 IPC MCP 请求事件失败: {}", e);
             }
+
+            server_state.broadcast(request).await;
         }
     });
 
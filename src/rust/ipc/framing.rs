@@ -0,0 +1,173 @@
+//! 长度前缀帧编解码
+//!
+//! 用 4 字节大端 `u32` 长度前缀 + JSON 负载代替按行读取，避免消息体里的
+//! 字面 `\n`（多行 prompt、Markdown 代码块很常见）把一条消息拆成两半。
+//!
+//! 为了在过渡期内兼容还没升级的旧版换行分隔客户端/服务端，读取时先探测
+//! 首字节：旧协议的 JSON 负载总是以 `{` 开头，而帧长度前缀在负载小于
+//! 16MiB 时首字节恒为 0（恰好 16MiB 时为 1），两者不会混淆。
+//!
+//! 兼容只做到"能读懂对方"还不够：如果读到的是旧版换行帧，回复也必须按
+//! 旧版格式写回去，否则对方会卡在 `read_line` 上等一条永远不会来的换行。
+//! [`read_frame`] 因此把探测到的格式一并返回，调用方用 [`write_frame_as`]
+//! 按原样式回复。
+
+use anyhow::{Context, Result};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// 单帧负载上限，超过视为异常/恶意数据，避免根据错误的长度前缀无限分配内存
+pub const MAX_FRAME_SIZE: u32 = 16 * 1024 * 1024;
+
+/// 一帧实际使用的协议格式，由 [`read_frame`] 探测得到
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameFormat {
+    /// 新版：4 字节大端长度前缀 + 负载
+    LengthPrefixed,
+    /// 旧版：按行分隔的 JSON，过渡期内兼容用
+    LegacyNewline,
+}
+
+/// 写入一帧（新版长度前缀格式）：4 字节大端长度前缀 + 负载
+pub async fn write_frame<W>(writer: &mut W, payload: &[u8]) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    write_frame_as(writer, payload, FrameFormat::LengthPrefixed).await
+}
+
+/// 按指定格式写入一帧
+///
+/// 用于回复一个用旧版换行格式发来请求的对端：即使本端已经升级，也要用对方
+/// 能读懂的格式回复，否则对方会一直阻塞在按行读取上。
+pub async fn write_frame_as<W>(writer: &mut W, payload: &[u8], format: FrameFormat) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    match format {
+        FrameFormat::LengthPrefixed => {
+            let len = u32::try_from(payload.len()).context("负载长度超过 u32 范围")?;
+            writer.write_all(&len.to_be_bytes()).await?;
+            writer.write_all(payload).await?;
+        }
+        FrameFormat::LegacyNewline => {
+            writer.write_all(payload).await?;
+            writer.write_all(b"\n").await?;
+        }
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+/// 读取一帧，返回负载字节和探测到的格式；连接正常关闭（EOF）时返回 `None`
+///
+/// 兼容旧版换行分隔 JSON：如果探测到的首字节是 `{`，按行读取一条 JSON 并返回。
+pub async fn read_frame<R>(reader: &mut R) -> Result<Option<(Vec<u8>, FrameFormat)>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let peeked = match reader.fill_buf().await {
+        Ok(buf) if buf.is_empty() => return Ok(None),
+        Ok(buf) => buf[0],
+        Err(e) => return Err(e).context("读取帧失败"),
+    };
+
+    if peeked == b'{' {
+        // 旧版换行分隔 JSON，逐行读取以兼容过渡期内未升级的一端；套一层
+        // MAX_FRAME_SIZE 的 `take` 上限，否则一个不带换行符的恶意/异常负载
+        // 会让 `read_line` 无界增长这个 `String`——和长度前缀分支要堵的是
+        // 同一个 OOM 口子
+        let mut line = String::new();
+        let mut limited = AsyncReadExt::take(&mut *reader, u64::from(MAX_FRAME_SIZE));
+        let bytes_read = limited.read_line(&mut line).await?;
+        if bytes_read == 0 {
+            return Ok(None);
+        }
+        if !line.ends_with('\n') {
+            anyhow::bail!("旧版换行帧超过上限 {} 字节且未找到换行符", MAX_FRAME_SIZE);
+        }
+        return Ok(Some((
+            line.trim().as_bytes().to_vec(),
+            FrameFormat::LegacyNewline,
+        )));
+    }
+
+    let mut len_buf = [0u8; 4];
+    if let Err(e) = reader.read_exact(&mut len_buf).await {
+        if e.kind() == std::io::ErrorKind::UnexpectedEof {
+            return Ok(None);
+        }
+        return Err(e).context("读取帧长度前缀失败");
+    }
+    let len = u32::from_be_bytes(len_buf);
+    if len > MAX_FRAME_SIZE {
+        anyhow::bail!("帧长度 {} 超过上限 {}", len, MAX_FRAME_SIZE);
+    }
+
+    let mut payload = vec![0u8; len as usize];
+    reader
+        .read_exact(&mut payload)
+        .await
+        .context("读取帧负载失败")?;
+    Ok(Some((payload, FrameFormat::LengthPrefixed)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use tokio::io::BufReader;
+
+    #[tokio::test]
+    async fn round_trips_length_prefixed_frame() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, b"hello").await.unwrap();
+
+        let mut reader = BufReader::new(Cursor::new(buf));
+        let (payload, format) = read_frame(&mut reader).await.unwrap().unwrap();
+        assert_eq!(payload, b"hello");
+        assert_eq!(format, FrameFormat::LengthPrefixed);
+    }
+
+    #[tokio::test]
+    async fn reads_legacy_newline_frame_and_remembers_format() {
+        let mut reader = BufReader::new(Cursor::new(b"{\"id\":\"1\"}\n".to_vec()));
+        let (payload, format) = read_frame(&mut reader).await.unwrap().unwrap();
+        assert_eq!(payload, b"{\"id\":\"1\"}");
+        assert_eq!(format, FrameFormat::LegacyNewline);
+    }
+
+    #[tokio::test]
+    async fn write_frame_as_legacy_newline_emits_newline_delimited_payload() {
+        let mut buf = Vec::new();
+        write_frame_as(&mut buf, b"{\"id\":\"1\"}", FrameFormat::LegacyNewline)
+            .await
+            .unwrap();
+        assert_eq!(buf, b"{\"id\":\"1\"}\n");
+    }
+
+    #[tokio::test]
+    async fn rejects_oversize_frame() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&(MAX_FRAME_SIZE + 1).to_be_bytes());
+
+        let mut reader = BufReader::new(Cursor::new(buf));
+        let err = read_frame(&mut reader).await.unwrap_err();
+        assert!(err.to_string().contains("超过上限"));
+    }
+
+    #[tokio::test]
+    async fn returns_none_on_clean_eof() {
+        let mut reader = BufReader::new(Cursor::new(Vec::new()));
+        assert!(read_frame(&mut reader).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_oversize_legacy_frame_without_newline() {
+        let mut buf = vec![b'{'];
+        buf.extend(std::iter::repeat(b'a').take(MAX_FRAME_SIZE as usize + 1));
+
+        let mut reader = BufReader::new(Cursor::new(buf));
+        let err = read_frame(&mut reader).await.unwrap_err();
+        assert!(err.to_string().contains("超过上限"));
+    }
+}
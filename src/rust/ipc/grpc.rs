@@ -0,0 +1,370 @@
+//! 可选的 gRPC/Tonic 传输
+//!
+//! 本地 Unix socket/命名管道要求 MCP 服务器和 UI（等）共享同一台机器的文件系统，
+//! 对跑在容器或 SSH 远端的无头 MCP 服务器没法用。这个模块提供一个基于 Tonic 的
+//! 替代传输：UI 主动拨号连接到 MCP 侧暴露的 `PopupService`，开一条双向流，
+//! MCP 在流上推送 `PopupRequest`，UI 回传 `PopupResponse`，按 `id` 复用和本地
+//! IPC 完全一致的多路复用/广播模型（见 [`super::server::IpcServerState`]）。
+//!
+//! 鉴权靠一对不对称的 Tonic 拦截器：服务端的 [`BearerTokenInterceptor`] 校验
+//! Bearer token（定长比较，避免时序旁路），客户端的 [`BearerTokenSender`] 负责
+//! 把 token 写进请求头——两者职责不能对调，写的一方不查，查的一方不写。token
+//! 本身通过 [`required_token_from_env`] 读取，未配置或为空都直接拒绝启动。
+//! 连接本身强制跑在 TLS 之上：[`server_tls_config_from_env`]/
+//! [`client_tls_config_from_env`] 从环境变量加载证书，任何一边缺了配置就直接
+//! 拒绝监听/拨号，不允许明文兜底。
+
+pub mod proto {
+    tonic::include_proto!("hengjing.ipc");
+}
+
+use std::pin::Pin;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use futures::Stream;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity, ServerTlsConfig};
+use tonic::{Request, Response, Status, Streaming};
+
+use proto::popup_service_client::PopupServiceClient;
+use proto::popup_service_server::{PopupService, PopupServiceServer};
+use proto::{PopupRequest as ProtoPopupRequest, PopupResponse as ProtoPopupResponse};
+
+use super::server::{IpcServerState, OutboundEvent, PendingEvictionGuard};
+use super::IpcRequest;
+use crate::log_important;
+
+/// 服务端侧拦截器：校验 `authorization: Bearer <token>` 元数据
+#[derive(Clone)]
+pub struct BearerTokenInterceptor {
+    expected_header: String,
+}
+
+impl BearerTokenInterceptor {
+    pub fn new(token: impl Into<String>) -> Self {
+        Self {
+            expected_header: format!("Bearer {}", token.into()),
+        }
+    }
+}
+
+impl tonic::service::Interceptor for BearerTokenInterceptor {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        match request.metadata().get("authorization") {
+            Some(value) if constant_time_eq(value.as_bytes(), self.expected_header.as_bytes()) => {
+                Ok(request)
+            }
+            _ => Err(Status::unauthenticated("缺少或无效的 Bearer token")),
+        }
+    }
+}
+
+/// 客户端侧拦截器：把 token 写进每个请求的 `authorization` 元数据。
+///
+/// 这是和 [`BearerTokenInterceptor`] 不对称的另一半——服务端的拦截器只管"查",
+/// 不会主动往出站请求上"写"，之前误把校验用的拦截器装在了客户端上，结果请求
+/// 里压根没带 token，服务端一查就是 `Unauthenticated`。
+#[derive(Clone)]
+pub struct BearerTokenSender {
+    header_value: String,
+}
+
+impl BearerTokenSender {
+    pub fn new(token: impl Into<String>) -> Self {
+        Self {
+            header_value: format!("Bearer {}", token.into()),
+        }
+    }
+}
+
+impl tonic::service::Interceptor for BearerTokenSender {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let value = self
+            .header_value
+            .parse()
+            .map_err(|_| Status::internal("Bearer token 包含非法字符，无法写入请求头"))?;
+        request.metadata_mut().insert("authorization", value);
+        Ok(request)
+    }
+}
+
+/// 定长比较两段字节，避免按字节提前退出，减少 token 校验泄露匹配了多少字节的
+/// 时序信息（长度不一致时仍会提前返回，但 `expected_header` 长度本身不是秘密）
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut diff = 0u8;
+    for (x, y) in a.iter().zip(b.iter()) {
+        diff |= x ^ y;
+    }
+    diff == 0
+}
+
+/// 从环境变量读取 gRPC 鉴权 token，未配置或为空都直接拒绝。
+///
+/// 远端场景下这个 token 是唯一的身份凭证，`unwrap_or_default()` 式的"缺了就用
+/// 空字符串"会让期望头变成裸的 `"Bearer "`，任何不带 token 的客户端都能通过。
+pub fn required_token_from_env() -> Result<String> {
+    let token = std::env::var("HENGJING_IPC_GRPC_TOKEN")
+        .context("未配置 HENGJING_IPC_GRPC_TOKEN，拒绝以空 token 暴露 gRPC 弹窗服务")?;
+    if token.is_empty() {
+        anyhow::bail!("HENGJING_IPC_GRPC_TOKEN 不能为空，拒绝以空 token 暴露 gRPC 弹窗服务");
+    }
+    Ok(token)
+}
+
+/// 从环境变量加载监听侧 TLS 配置：证书和私钥都必须配置，任何一个缺失都直接
+/// 报错而不是退化为明文监听——跨机器场景下 Bearer token 不该是唯一的防线
+pub fn server_tls_config_from_env() -> Result<ServerTlsConfig> {
+    let cert_path = std::env::var("HENGJING_IPC_GRPC_TLS_CERT")
+        .context("未配置 HENGJING_IPC_GRPC_TLS_CERT，拒绝以明文监听 gRPC 弹窗服务")?;
+    let key_path = std::env::var("HENGJING_IPC_GRPC_TLS_KEY")
+        .context("未配置 HENGJING_IPC_GRPC_TLS_KEY，拒绝以明文监听 gRPC 弹窗服务")?;
+    let cert = std::fs::read(&cert_path).context("读取 gRPC TLS 证书失败")?;
+    let key = std::fs::read(&key_path).context("读取 gRPC TLS 私钥失败")?;
+    Ok(ServerTlsConfig::new().identity(Identity::from_pem(cert, key)))
+}
+
+/// 从环境变量加载拨号侧 TLS 配置：必须配置 CA 证书来校验远端身份，
+/// 可选的 `HENGJING_IPC_GRPC_TLS_DOMAIN` 用于在 endpoint 不是证书 CN/SAN 时覆盖校验用的域名
+pub fn client_tls_config_from_env() -> Result<ClientTlsConfig> {
+    let ca_path = std::env::var("HENGJING_IPC_GRPC_TLS_CA")
+        .context("未配置 HENGJING_IPC_GRPC_TLS_CA，拒绝以明文连接远端 gRPC 弹窗服务")?;
+    let ca_cert = std::fs::read(&ca_path).context("读取 gRPC TLS CA 证书失败")?;
+    let mut tls_config = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(ca_cert));
+    if let Ok(domain) = std::env::var("HENGJING_IPC_GRPC_TLS_DOMAIN") {
+        tls_config = tls_config.domain_name(domain);
+    }
+    Ok(tls_config)
+}
+
+/// `PopupService` 的服务端实现，把远端 UI 的流桥接到 [`IpcServerState`]
+///
+/// 一条流就是一个"连接"，复用和本地窗口完全一样的 `register_connection` /
+/// `broadcast` / 背压丢弃逻辑，因此远端 UI 和本地 Tauri 窗口可以同时在线。
+pub struct PopupServiceImpl {
+    state: Arc<IpcServerState>,
+}
+
+impl PopupServiceImpl {
+    pub fn new(state: Arc<IpcServerState>) -> Self {
+        Self { state }
+    }
+
+    pub fn into_server(
+        self,
+        token: impl Into<String>,
+    ) -> tonic::service::interceptor::InterceptedService<PopupServiceServer<Self>, BearerTokenInterceptor> {
+        PopupServiceServer::with_interceptor(self, BearerTokenInterceptor::new(token))
+    }
+}
+
+type PopupRequestStream = Pin<Box<dyn Stream<Item = Result<ProtoPopupRequest, Status>> + Send + 'static>>;
+
+#[tonic::async_trait]
+impl PopupService for PopupServiceImpl {
+    type PopupStreamStream = PopupRequestStream;
+
+    async fn popup_stream(
+        &self,
+        request: Request<Streaming<ProtoPopupResponse>>,
+    ) -> Result<Response<Self::PopupStreamStream>, Status> {
+        let mut inbound = request.into_inner();
+        let state = Arc::clone(&self.state);
+        let (connection_id, queue) = state.register_connection().await;
+        let (outbound_tx, outbound_rx) = mpsc::channel::<Result<ProtoPopupRequest, Status>>(32);
+
+        // 把广播给这个连接的弹窗请求转成 proto 消息推给远端 UI
+        let forward_state = Arc::clone(&state);
+        tokio::spawn(async move {
+            loop {
+                let event = match queue.recv().await {
+                    Some(event) => event,
+                    // 连接已被注销（流断开/对端掉线），队列排空，退出任务
+                    None => break,
+                };
+                let proto_request = match event {
+                    OutboundEvent::Popup(req) => ProtoPopupRequest {
+                        id: req.id,
+                        message: req.message,
+                        predefined_options: req.predefined_options.unwrap_or_default(),
+                        is_markdown: req.is_markdown,
+                    },
+                    // 远端 UI 没有"本地窗口"的概念可以关闭，这两类提示对它没有意义
+                    OutboundEvent::AlreadyAnswered { .. } | OutboundEvent::Missed => continue,
+                };
+                if outbound_tx.send(Ok(proto_request)).await.is_err() {
+                    break;
+                }
+            }
+            forward_state.unregister_connection(connection_id).await;
+        });
+
+        // 把远端 UI 回传的响应喂回等待中的请求；success=false 视为这个连接"放弃
+        // 作答"（对应本地的 cancelled），留给其它连接来回答
+        let response_state = Arc::clone(&state);
+        tokio::spawn(async move {
+            while let Ok(Some(response)) = inbound.message().await {
+                let result = if response.partial {
+                    response_state
+                        .send_chunk(&response.id, response.response)
+                        .await
+                } else {
+                    response_state
+                        .send_response(&response.id, response.response, !response.success)
+                        .await
+                };
+                if let Err(e) = result {
+                    log_important!(warn, "处理远端 gRPC 响应失败: {}", e);
+                }
+            }
+            // 入站流结束即对端断开，注销连接，让上面的转发任务收到 `None` 退出
+            response_state.unregister_connection(connection_id).await;
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(outbound_rx))))
+    }
+}
+
+/// UI 侧：拨号连接到远端 MCP 服务器的 `PopupService`，把收到的每个弹窗请求
+/// 注入本地 `IpcServerState`（走和本地窗口一样的广播/应答路径），并把本地的
+/// 应答结果回传给远端
+pub async fn run_ui_client(
+    endpoint: String,
+    token: String,
+    state: Arc<IpcServerState>,
+) -> Result<()> {
+    let tls_config = client_tls_config_from_env()?;
+    let channel = Channel::from_shared(endpoint.clone())
+        .context("无效的 gRPC endpoint")?
+        .tls_config(tls_config)
+        .context("配置 gRPC TLS 失败")?
+        .connect()
+        .await
+        .context("连接远端 MCP gRPC 服务失败")?;
+    let mut client =
+        PopupServiceClient::with_interceptor(channel, BearerTokenSender::new(token));
+
+    let (outbound_tx, outbound_rx) = mpsc::channel::<ProtoPopupResponse>(32);
+    let mut inbound = client
+        .popup_stream(ReceiverStream::new(outbound_rx))
+        .await
+        .context("建立 gRPC 双向流失败")?
+        .into_inner();
+
+    log_important!(info, "已连接到远端 MCP gRPC 服务: {}", endpoint);
+
+    while let Some(proto_request) = inbound.message().await.context("读取远端弹窗请求失败")? {
+        let request = IpcRequest {
+            id: proto_request.id,
+            message: proto_request.message,
+            predefined_options: if proto_request.predefined_options.is_empty() {
+                None
+            } else {
+                Some(proto_request.predefined_options)
+            },
+            is_markdown: proto_request.is_markdown,
+        };
+        let request_id = request.id.clone();
+
+        let (response_tx, mut response_rx) = tokio::sync::oneshot::channel();
+        let mut chunk_rx = state.set_pending(request_id.clone(), response_tx).await;
+
+        // 通知本地前端有新请求（和本地 Unix socket 连接走同一条路径）
+        let _ = state.get_request_tx().send(request).await;
+
+        let reply_tx = outbound_tx.clone();
+        let request_id_for_task = request_id.clone();
+        let eviction_state = Arc::clone(&state);
+        tokio::spawn(async move {
+            // 回传失败或整条 gRPC 流提前断开时，靠这个 guard 清理掉
+            // pending_requests 里的残留条目，避免之后 send_response 看起来
+            // "成功"实则把答案丢进了一个没人在听的 oneshot
+            let _eviction_guard = PendingEvictionGuard::new(eviction_state, request_id_for_task.clone());
+            loop {
+                tokio::select! {
+                    biased;
+                    response = &mut response_rx => {
+                        let proto_response = match response {
+                            Ok(response) => ProtoPopupResponse {
+                                id: request_id_for_task.clone(),
+                                response,
+                                success: true,
+                                error: String::new(),
+                                partial: false,
+                            },
+                            Err(_) => ProtoPopupResponse {
+                                id: request_id_for_task.clone(),
+                                response: String::new(),
+                                success: false,
+                                error: "响应通道已关闭".to_string(),
+                                partial: false,
+                            },
+                        };
+                        let _ = reply_tx.send(proto_response).await;
+                        break;
+                    }
+                    chunk = chunk_rx.recv() => {
+                        let Some(chunk) = chunk else { continue };
+                        let proto_chunk = ProtoPopupResponse {
+                            id: chunk.id,
+                            response: chunk.chunk,
+                            success: true,
+                            error: String::new(),
+                            partial: true,
+                        };
+                        if reply_tx.send(proto_chunk).await.is_err() {
+                            break;
+                        }
+                    }
+                    // 整条 gRPC 流已断开（出站接收端被丢弃），不再等待本地应答
+                    _ = reply_tx.closed() => break,
+                }
+            }
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_time_eq_matches_identical_bytes() {
+        assert!(constant_time_eq(b"Bearer secret-token", b"Bearer secret-token"));
+    }
+
+    #[test]
+    fn constant_time_eq_rejects_different_content_same_length() {
+        assert!(!constant_time_eq(b"Bearer secret-token", b"Bearer wrong-token!"));
+    }
+
+    #[test]
+    fn constant_time_eq_rejects_different_length() {
+        assert!(!constant_time_eq(b"Bearer short", b"Bearer much-longer-token"));
+    }
+
+    #[test]
+    fn sender_injects_header_that_verifier_accepts() {
+        use tonic::service::Interceptor;
+
+        let mut sender = BearerTokenSender::new("secret-token");
+        let request = sender.call(Request::new(())).unwrap();
+
+        let mut verifier = BearerTokenInterceptor::new("secret-token");
+        assert!(verifier.call(request).is_ok());
+    }
+
+    #[test]
+    fn verifier_rejects_request_with_no_authorization_header() {
+        use tonic::service::Interceptor;
+
+        let mut verifier = BearerTokenInterceptor::new("secret-token");
+        assert!(verifier.call(Request::new(())).is_err());
+    }
+}
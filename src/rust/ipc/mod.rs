@@ -7,10 +7,16 @@ use std::path::PathBuf;
 
 pub mod client;
 pub mod commands;
+pub mod framing;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 pub mod server;
 
 pub use client::IpcClient;
-pub use commands::{IpcStateWrapper, send_ipc_response, start_ipc_server};
+pub use commands::{
+    IpcStateWrapper, register_ipc_connection, send_ipc_chunk, send_ipc_response,
+    start_ipc_server, unregister_ipc_connection,
+};
 pub use server::{cleanup_socket, IpcServer, IpcServerState};
 
 /// IPC 请求
@@ -23,12 +29,27 @@ pub struct IpcRequest {
 }
 
 /// IPC 响应
+///
+/// `partial` 为 `true` 时表示这是一条流式进度消息（打字状态/中间结果），
+/// 真正的最终答案只有一条，`partial` 为 `false`（默认值，兼容旧版客户端）。
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IpcResponse {
     pub id: String,
     pub response: String,
     pub success: bool,
     pub error: Option<String>,
+    #[serde(default)]
+    pub partial: bool,
+}
+
+/// 一条流式进度消息，在最终响应送达之前推送给等待中的调用方
+///
+/// 没有"终帧"的概念——真正的最终答案只走 `send_response`/`IpcResponse`，
+/// 流式进度消息永远只是 partial。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamChunk {
+    pub id: String,
+    pub chunk: String,
 }
 
 impl From<&crate::mcp::types::PopupRequest> for IpcRequest {
@@ -48,9 +69,12 @@ pub fn get_socket_path() -> PathBuf {
     std::env::temp_dir().join("hengjing-ui.sock")
 }
 
-/// Windows 暂未实现（占位，避免跨平台编译错误）
+/// 获取 Windows 命名管道路径
+///
+/// Windows 下用命名管道代替 Unix socket，返回值实际是管道名而非文件路径，
+/// 沿用 `PathBuf` 只是为了和 Unix 分支保持同一个函数签名。
 #[cfg(windows)]
 pub fn get_socket_path() -> PathBuf {
-    std::env::temp_dir().join("hengjing-ui.sock")
+    PathBuf::from(r"\\.\pipe\hengjing-ui")
 }
 
@@ -3,23 +3,95 @@
 //! 在 UI 进程中运行，监听来自 MCP 的请求
 
 use anyhow::Result;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite, BufReader};
+use tokio::sync::{mpsc, oneshot, Mutex, Notify};
 
-use super::{get_socket_path, IpcRequest, IpcResponse};
+use super::framing::{read_frame, write_frame_as, FrameFormat};
+use super::{get_socket_path, IpcRequest, IpcResponse, StreamChunk};
 use crate::log_important;
 
-/// 等待中的请求
-pub struct PendingRequest {
-    pub request: IpcRequest,
-    pub response_tx: oneshot::Sender<String>,
+/// 每个 UI 连接的积压队列容量，超过后清空积压、换成一条 `Missed` 标记，
+/// 不让卡死/缓慢的窗口拖慢广播或无限占用内存
+const CONNECTION_QUEUE_CAPACITY: usize = 16;
+
+/// 推送给某个已注册 UI 连接的事件
+#[derive(Debug, Clone)]
+pub enum OutboundEvent {
+    /// 新的弹窗请求，需要广播给所有连接
+    Popup(IpcRequest),
+    /// 该请求已经被其它窗口回答，对应弹窗可以关闭了
+    AlreadyAnswered { request_id: String },
+    /// 本连接消费太慢，有弹窗被丢弃，用这一条提示代替被挤掉的那些
+    Missed,
+}
+
+/// 单个 UI 连接的积压队列：满了就清空、只留一条 `Missed`，而不是阻塞发送方
+pub(crate) struct OutboundQueue {
+    items: Mutex<VecDeque<OutboundEvent>>,
+    notify: Notify,
+    /// 连接被注销后置位，唤醒并结束卡在 `recv` 上的转发任务，避免它在窗口
+    /// 关闭后仍然抱着这份队列的 `Arc` 永久挂起
+    closed: AtomicBool,
+}
+
+impl OutboundQueue {
+    fn new() -> Self {
+        Self {
+            items: Mutex::new(VecDeque::new()),
+            notify: Notify::new(),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    async fn push(&self, event: OutboundEvent) {
+        let mut items = self.items.lock().await;
+        if items.len() >= CONNECTION_QUEUE_CAPACITY {
+            items.clear();
+            items.push_back(OutboundEvent::Missed);
+        } else {
+            items.push_back(event);
+        }
+        drop(items);
+        self.notify.notify_one();
+    }
+
+    /// 标记队列已关闭，唤醒阻塞在 `recv` 上的转发任务让它退出
+    fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+        self.notify.notify_one();
+    }
+
+    /// 等待并取出下一个事件；连接已被注销且积压排空后返回 `None`，调用方应
+    /// 把它当作退出信号，而不是继续轮询
+    pub(crate) async fn recv(self: &Arc<Self>) -> Option<OutboundEvent> {
+        loop {
+            if let Some(event) = self.items.lock().await.pop_front() {
+                return Some(event);
+            }
+            if self.closed.load(Ordering::Relaxed) {
+                return None;
+            }
+            self.notify.notified().await;
+        }
+    }
+}
+
+/// 一个等待响应的请求：最终答案走 `response_tx`，期间的流式进度走 `chunk_tx`
+struct PendingEntry {
+    response_tx: oneshot::Sender<String>,
+    chunk_tx: mpsc::Sender<StreamChunk>,
 }
 
 /// IPC 服务器状态
 pub struct IpcServerState {
-    /// 当前等待响应的请求
-    pending_request: Mutex<Option<PendingRequest>>,
+    /// 所有等待响应的请求，按请求 id 索引，支持多个并发弹窗
+    pending_requests: Mutex<HashMap<String, PendingEntry>>,
+    /// 已注册的 UI 连接（多个窗口/设备），按连接 id 索引
+    connections: Mutex<HashMap<u64, Arc<OutboundQueue>>>,
+    next_connection_id: AtomicU64,
     /// 新请求通道发送端
     request_tx: mpsc::Sender<IpcRequest>,
 }
@@ -27,40 +99,201 @@ pub struct IpcServerState {
 impl IpcServerState {
     pub fn new(request_tx: mpsc::Sender<IpcRequest>) -> Self {
         Self {
-            pending_request: Mutex::new(None),
+            pending_requests: Mutex::new(HashMap::new()),
+            connections: Mutex::new(HashMap::new()),
+            next_connection_id: AtomicU64::new(1),
             request_tx,
         }
     }
-    
-    /// 设置当前等待的请求
-    pub async fn set_pending(&self, request: IpcRequest, response_tx: oneshot::Sender<String>) {
-        let mut pending = self.pending_request.lock().await;
-        *pending = Some(PendingRequest { request, response_tx });
+
+    /// 注册一个新的 UI 连接（一个窗口/设备），返回连接 id 和事件队列
+    pub async fn register_connection(&self) -> (u64, Arc<OutboundQueue>) {
+        let id = self.next_connection_id.fetch_add(1, Ordering::Relaxed);
+        let queue = Arc::new(OutboundQueue::new());
+        self.connections.lock().await.insert(id, Arc::clone(&queue));
+        (id, queue)
     }
-    
+
+    /// 注销一个 UI 连接（窗口关闭/断开），并唤醒它的转发任务让其退出
+    pub async fn unregister_connection(&self, id: u64) {
+        if let Some(queue) = self.connections.lock().await.remove(&id) {
+            queue.close();
+        }
+    }
+
+    /// 把一个弹窗请求广播给当前所有已注册的 UI 连接
+    pub async fn broadcast(&self, request: IpcRequest) {
+        let connections = self.connections.lock().await;
+        for queue in connections.values() {
+            queue.push(OutboundEvent::Popup(request.clone())).await;
+        }
+    }
+
+    /// 登记并广播一个请求，等待第一个权威应答
+    ///
+    /// 给没有走本地 Unix socket/命名管道连接的调用方使用（例如 gRPC 传输里
+    /// MCP 侧直接持有这份状态的场景），复用和本地连接完全一致的多路复用模型。
+    /// 期间收到的每条流式进度都会交给 `on_progress`；调用方即使不关心进度，
+    /// 也要把 `set_pending` 返回的 `chunk_rx` 排空——否则这个请求自己的进度
+    /// 积压会一直占着（`send_chunk` 写满后会丢弃新进度，不会阻塞），所以这里
+    /// 总是在后台任务里消费它。
+    pub async fn submit(
+        self: &Arc<Self>,
+        request: IpcRequest,
+        mut on_progress: impl FnMut(String) + Send + 'static,
+    ) -> Result<String> {
+        let request_id = request.id.clone();
+        let (response_tx, response_rx) = oneshot::channel();
+        let mut chunk_rx = self.set_pending(request_id.clone(), response_tx).await;
+        let _guard = PendingEvictionGuard::new(Arc::clone(self), request_id);
+
+        tokio::spawn(async move {
+            while let Some(chunk) = chunk_rx.recv().await {
+                on_progress(chunk.chunk);
+            }
+        });
+
+        self.broadcast(request).await;
+        response_rx
+            .await
+            .map_err(|_| anyhow::anyhow!("响应通道已关闭"))
+    }
+
+    /// 通知所有连接：某个请求已经被回答，还没处理的同 id 弹窗可以关闭了
+    async fn notify_answered(&self, request_id: &str) {
+        let connections = self.connections.lock().await;
+        for queue in connections.values() {
+            queue
+                .push(OutboundEvent::AlreadyAnswered {
+                    request_id: request_id.to_string(),
+                })
+                .await;
+        }
+    }
+
+    /// 登记一个等待响应的请求，同时返回用于转发流式进度的接收端
+    pub async fn set_pending(
+        &self,
+        request_id: String,
+        response_tx: oneshot::Sender<String>,
+    ) -> mpsc::Receiver<StreamChunk> {
+        let (chunk_tx, chunk_rx) = mpsc::channel(32);
+        let mut pending = self.pending_requests.lock().await;
+        pending.insert(
+            request_id,
+            PendingEntry {
+                response_tx,
+                chunk_tx,
+            },
+        );
+        chunk_rx
+    }
+
     /// 发送响应给等待中的请求
-    pub async fn send_response(&self, request_id: &str, response: String) -> Result<()> {
-        let mut pending = self.pending_request.lock().await;
-        if let Some(req) = pending.take() {
-            if req.request.id == request_id {
-                let _ = req.response_tx.send(response);
-                Ok(())
-            } else {
-                // 请求 ID 不匹配，放回去
-                *pending = Some(req);
-                anyhow::bail!("请求 ID 不匹配")
+    ///
+    /// `cancelled` 为 `true` 表示这个窗口只是关闭了弹窗、并没有真正作答（用户切换到
+    /// 了另一个窗口回答），此时不会消费这个请求，留给其它窗口去响应；第一个非取消
+    /// 的响应才是权威答案，一旦产生就会通知其余连接关闭各自的弹窗。
+    pub async fn send_response(
+        &self,
+        request_id: &str,
+        response: String,
+        cancelled: bool,
+    ) -> Result<()> {
+        if cancelled {
+            return Ok(());
+        }
+
+        let answered = {
+            let mut pending = self.pending_requests.lock().await;
+            match pending.remove(request_id) {
+                Some(entry) => {
+                    let _ = entry.response_tx.send(response);
+                    true
+                }
+                None => false,
             }
+        };
+
+        if answered {
+            self.notify_answered(request_id).await;
+            Ok(())
         } else {
-            anyhow::bail!("没有等待中的请求")
+            anyhow::bail!("没有等待中的请求: {}", request_id)
         }
     }
-    
+
+    /// 推送一条流式进度消息给等待中的请求（不消费该请求，真正的最终答案仍走 `send_response`）
+    ///
+    /// 只在短暂持锁期间克隆出 `chunk_tx`，发送本身在锁外进行：`pending_requests`
+    /// 这把锁是所有并发请求共用的，如果在锁内 `.await` 一个可能阻塞的 send，
+    /// 一个消费跟不上的连接就会通过这把锁拖慢其它请求的 `set_pending`/`send_response`，
+    /// 重新引入 chunk0-2 想要消除的排队问题。跟不上时直接丢弃这条进度（和
+    /// `OutboundQueue` 的背压策略一致），而不是阻塞发送方。
+    pub async fn send_chunk(&self, request_id: &str, chunk: String) -> Result<()> {
+        let chunk_tx = {
+            let pending = self.pending_requests.lock().await;
+            match pending.get(request_id) {
+                Some(entry) => entry.chunk_tx.clone(),
+                None => anyhow::bail!("没有等待中的请求: {}", request_id),
+            }
+        };
+
+        let stream_chunk = StreamChunk {
+            id: request_id.to_string(),
+            chunk,
+        };
+        match chunk_tx.try_send(stream_chunk) {
+            Ok(()) => Ok(()),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                log_important!(warn, "流式进度积压已满，丢弃一条进度: {}", request_id);
+                Ok(())
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                anyhow::bail!("流式进度通道已关闭")
+            }
+        }
+    }
+
+    /// 清理一个已不再等待响应的请求（客户端断开/超时）
+    ///
+    /// 避免连接中途断开时 pending_requests 中留下永远不会被回答的条目
+    pub async fn remove_pending(&self, request_id: &str) {
+        let mut pending = self.pending_requests.lock().await;
+        pending.remove(request_id);
+    }
+
     /// 获取请求发送通道
     pub fn get_request_tx(&self) -> mpsc::Sender<IpcRequest> {
         self.request_tx.clone()
     }
 }
 
+/// 在丢弃时清理 `pending_requests` 中对应条目的 guard
+///
+/// 正常应答路径下 `send_response` 已经 `remove` 过，这里再 `remove` 一次是幂等的空操作；
+/// 只有连接异常中断导致 response_rx 被提前丢弃时，才会真正清掉孤儿条目。
+pub(crate) struct PendingEvictionGuard {
+    state: Arc<IpcServerState>,
+    request_id: String,
+}
+
+impl PendingEvictionGuard {
+    pub(crate) fn new(state: Arc<IpcServerState>, request_id: String) -> Self {
+        Self { state, request_id }
+    }
+}
+
+impl Drop for PendingEvictionGuard {
+    fn drop(&mut self) {
+        let state = Arc::clone(&self.state);
+        let request_id = self.request_id.clone();
+        tokio::spawn(async move {
+            state.remove_pending(&request_id).await;
+        });
+    }
+}
+
 /// IPC 服务器
 pub struct IpcServer {
     state: Arc<IpcServerState>,
@@ -119,9 +352,51 @@ impl IpcServer {
         Ok(())
     }
     
+    /// 启动 IPC 服务器（Windows 命名管道）
     #[cfg(windows)]
     pub async fn start(&self) -> Result<()> {
-        log_important!(warn, "Windows IPC 服务器暂未实现");
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        let pipe_name = get_socket_path();
+        let pipe_name = pipe_name.to_string_lossy().to_string();
+
+        // 先创建第一个管道实例，后续每接受一个连接就补建下一个，
+        // 这样才能支持多个客户端并发连接而不会互相阻塞
+        let mut server = ServerOptions::new()
+            .first_pipe_instance(true)
+            .create(&pipe_name)?;
+        log_important!(info, "IPC 服务器已启动: {}", pipe_name);
+
+        let state = Arc::clone(&self.state);
+
+        tokio::spawn(async move {
+            loop {
+                match server.connect().await {
+                    Ok(()) => {
+                        // 立即补建下一个管道实例，避免并发客户端被阻塞
+                        let connected = server;
+                        server = match ServerOptions::new().create(&pipe_name) {
+                            Ok(next) => next,
+                            Err(e) => {
+                                log_important!(error, "创建下一个 IPC 管道实例失败: {}", e);
+                                break;
+                            }
+                        };
+
+                        let state_clone = Arc::clone(&state);
+                        tokio::spawn(async move {
+                            if let Err(e) = handle_connection(connected, state_clone).await {
+                                log_important!(error, "处理 IPC 连接失败: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        log_important!(error, "接受 IPC 连接失败: {}", e);
+                    }
+                }
+            }
+        });
+
         Ok(())
     }
 }
@@ -132,59 +407,105 @@ async fn handle_connection(
     stream: tokio::net::UnixStream,
     state: Arc<IpcServerState>,
 ) -> Result<()> {
-    let (reader, mut writer) = stream.into_split();
+    let (reader, writer) = stream.into_split();
+    handle_connection_io(reader, writer, state).await
+}
+
+/// 处理单个 IPC 连接（Windows 命名管道）
+#[cfg(windows)]
+async fn handle_connection(
+    stream: tokio::net::windows::named_pipe::NamedPipeServer,
+    state: Arc<IpcServerState>,
+) -> Result<()> {
+    let (reader, writer) = tokio::io::split(stream);
+    handle_connection_io(reader, writer, state).await
+}
+
+/// 读写请求/响应的通用逻辑，Unix/Windows 共用同一套长度前缀 JSON 帧协议
+/// （`read_frame`/`write_frame_as`，兼容过渡期内的旧版换行分隔对端）
+async fn handle_connection_io<R, W>(
+    reader: R,
+    mut writer: W,
+    state: Arc<IpcServerState>,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
     let mut buf_reader = BufReader::new(reader);
-    let mut line = String::new();
-    
-    // 读取请求
-    let bytes_read = buf_reader.read_line(&mut line).await?;
-    if bytes_read == 0 {
-        return Ok(());
-    }
-    
+
+    // 读取请求（长度前缀帧，兼容过渡期内的旧版换行分隔客户端）；记住探测到的
+    // 格式，回复时原样使用，否则旧版客户端会卡在按行读取上收不到回应
+    let (frame, format) = match read_frame(&mut buf_reader).await? {
+        Some(frame) => frame,
+        None => return Ok(()),
+    };
+
     // 解析请求
-    let request: IpcRequest = serde_json::from_str(line.trim())?;
+    let request: IpcRequest = serde_json::from_slice(&frame)?;
     let request_id = request.id.clone();
     
     log_important!(info, "收到 IPC 请求: {}", request_id);
     
     // 创建响应通道
-    let (response_tx, response_rx) = oneshot::channel();
-    
-    // 设置等待中的请求
-    state.set_pending(request.clone(), response_tx).await;
-    
+    let (response_tx, mut response_rx) = oneshot::channel();
+
+    // 登记等待中的请求，同时拿到流式进度的接收端
+    let mut chunk_rx = state.set_pending(request_id.clone(), response_tx).await;
+    // 若 response_rx 在收到响应前被丢弃（连接中途断开/被取消），
+    // 借助这个 guard 把 pending_requests 里的残留条目清理掉
+    let _eviction_guard = PendingEvictionGuard::new(Arc::clone(&state), request_id.clone());
+
     // 通知前端有新请求
     let _ = state.get_request_tx().send(request).await;
-    
-    // 等待响应
-    match response_rx.await {
-        Ok(response) => {
-            let ipc_response = IpcResponse {
-                id: request_id,
-                response,
-                success: true,
-                error: None,
-            };
-            let response_json = serde_json::to_string(&ipc_response)?;
-            writer.write_all(response_json.as_bytes()).await?;
-            writer.write_all(b"\n").await?;
-            writer.flush().await?;
-        }
-        Err(_) => {
-            let ipc_response = IpcResponse {
-                id: request_id,
-                response: String::new(),
-                success: false,
-                error: Some("响应通道已关闭".to_string()),
-            };
-            let response_json = serde_json::to_string(&ipc_response)?;
-            writer.write_all(response_json.as_bytes()).await?;
-            writer.write_all(b"\n").await?;
-            writer.flush().await?;
+
+    // 在最终响应到达之前，持续把流式进度作为 partial 帧转发出去
+    let ipc_response = loop {
+        tokio::select! {
+            biased;
+            response = &mut response_rx => {
+                break match response {
+                    Ok(response) => IpcResponse {
+                        id: request_id.clone(),
+                        response,
+                        success: true,
+                        error: None,
+                        partial: false,
+                    },
+                    Err(_) => IpcResponse {
+                        id: request_id.clone(),
+                        response: String::new(),
+                        success: false,
+                        error: Some("响应通道已关闭".to_string()),
+                        partial: false,
+                    },
+                };
+            }
+            chunk = chunk_rx.recv() => {
+                let Some(chunk) = chunk else { continue };
+                // 旧版换行客户端只做一次 `read_line`、也没有 `partial` 字段，
+                // 多写一帧它会把第一条中间进度误当成最终答案；这条兼容路径只
+                // 保证旧客户端能收到唯一一帧最终响应，流式进度对它不存在，
+                // 直接丢弃而不是带着它一起违反"一行一个完整 JSON"的旧协议
+                if format == FrameFormat::LegacyNewline {
+                    continue;
+                }
+                let partial_response = IpcResponse {
+                    id: chunk.id,
+                    response: chunk.chunk,
+                    success: true,
+                    error: None,
+                    partial: true,
+                };
+                let chunk_json = serde_json::to_vec(&partial_response)?;
+                write_frame_as(&mut writer, &chunk_json, format).await?;
+            }
         }
-    }
-    
+    };
+
+    let response_json = serde_json::to_vec(&ipc_response)?;
+    write_frame_as(&mut writer, &response_json, format).await?;
+
     Ok(())
 }
 
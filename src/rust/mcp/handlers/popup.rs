@@ -9,8 +9,23 @@ use crate::log_important;
 
 /// 创建 Tauri 弹窗
 ///
-/// 优先通过 IPC 发送到已运行的 UI，失败则启动新进程
-pub async fn create_tauri_popup(request: &PopupRequest) -> Result<String> {
+/// 配置了远端 gRPC endpoint 时直接走 gRPC 传输（无头服务器场景，本机没有
+/// 可以 spawn 的 UI 进程）；否则优先通过本地 IPC 发送到已运行的 UI，失败则
+/// 回退为启动新进程。
+///
+/// `on_progress` 在最终答案到达之前，接住 UI 推送的每一条流式进度（打字状态/
+/// 部分结果），调用方（MCP 工具处理逻辑）负责把它转成真正的 MCP 进度通知；
+/// 这里只管把两种传输各自收到的 chunk 原样转交，不关心下游怎么用。
+pub async fn create_tauri_popup(
+    request: &PopupRequest,
+    mut on_progress: impl FnMut(String) + Send + 'static,
+) -> Result<String> {
+    #[cfg(feature = "grpc")]
+    if let Some(endpoint) = remote_grpc::configured_endpoint() {
+        log_important!(info, "已配置远端 gRPC endpoint，通过 gRPC 发送请求");
+        return remote_grpc::create_remote_popup(&endpoint, request, on_progress).await;
+    }
+
     // 尝试通过 IPC 发送到已运行的 UI
     let ipc_request = IpcRequest::from(request);
 
@@ -19,7 +34,9 @@ pub async fn create_tauri_popup(request: &PopupRequest) -> Result<String> {
 
     if ui_running {
         log_important!(info, "检测到 UI 正在运行，通过 IPC 发送请求");
-        match IpcClient::send_request(&ipc_request).await {
+        match IpcClient::send_request_with_progress(&ipc_request, |chunk| on_progress(chunk))
+            .await
+        {
             Ok(response) => {
                 log_important!(info, "IPC 响应成功");
                 return Ok(response);
@@ -121,3 +138,64 @@ fn is_executable(path: &Path) -> bool {
             .unwrap_or(false)
     }
 }
+
+/// 远端 gRPC 传输：配置了 endpoint 时，MCP 侧自己持有一份 `IpcServerState`，
+/// 并通过 gRPC 把它暴露出去，供跑在另一台机器上的 UI 拨号连入
+#[cfg(feature = "grpc")]
+mod remote_grpc {
+    use std::sync::Arc;
+
+    use anyhow::{Context, Result};
+    use tokio::sync::{mpsc, OnceCell};
+    use tonic::transport::Server;
+
+    use crate::ipc::grpc::{required_token_from_env, server_tls_config_from_env, PopupServiceImpl};
+    use crate::ipc::server::IpcServerState;
+    use crate::ipc::IpcRequest;
+    use crate::log_important;
+    use crate::mcp::types::PopupRequest;
+
+    static GRPC_STATE: OnceCell<Arc<IpcServerState>> = OnceCell::const_new();
+
+    /// 从环境变量读取远端 gRPC 配置：设置了监听地址才启用这条传输
+    pub fn configured_endpoint() -> Option<String> {
+        std::env::var("HENGJING_IPC_GRPC_LISTEN").ok()
+    }
+
+    async fn grpc_state(bind_addr: &str) -> Result<Arc<IpcServerState>> {
+        GRPC_STATE
+            .get_or_try_init(|| async {
+                let (request_tx, _request_rx) = mpsc::channel::<IpcRequest>(32);
+                let state = Arc::new(IpcServerState::new(request_tx));
+
+                let addr = bind_addr.parse().context("无效的 gRPC 监听地址")?;
+                let tls_config = server_tls_config_from_env()?;
+                let token = required_token_from_env()?;
+                let service = PopupServiceImpl::new(Arc::clone(&state)).into_server(token);
+                let server = Server::builder()
+                    .tls_config(tls_config)
+                    .context("配置 gRPC TLS 失败")?
+                    .add_service(service);
+                tokio::spawn(async move {
+                    log_important!(info, "gRPC 弹窗服务已启动: {}", addr);
+                    if let Err(e) = server.serve(addr).await {
+                        log_important!(error, "gRPC 弹窗服务退出: {}", e);
+                    }
+                });
+
+                Ok::<_, anyhow::Error>(state)
+            })
+            .await
+            .map(Arc::clone)
+    }
+
+    pub async fn create_remote_popup(
+        bind_addr: &str,
+        request: &PopupRequest,
+        on_progress: impl FnMut(String) + Send + 'static,
+    ) -> Result<String> {
+        let state = grpc_state(bind_addr).await?;
+        let ipc_request = IpcRequest::from(request);
+        state.submit(ipc_request, on_progress).await
+    }
+}